@@ -0,0 +1,253 @@
+use crate::influx_exporter::InfluxConfig;
+use btleplug::api::BDAddr;
+use serde::Deserialize;
+use std::{collections::HashMap, fmt, fs, path::Path, str::FromStr, time::Duration};
+
+/// User-facing configuration for a single known sensor.
+#[derive(Debug, Deserialize)]
+struct SensorConfig {
+    name: String,
+}
+
+/// Selects a bluetooth adapter to listen on, either by its position in the list `btleplug`
+/// reports or by its interface name (e.g. `"hci1"`).
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum RawAdapterSelector {
+    Index(usize),
+    Name(String),
+}
+
+/// Selects a bluetooth adapter to listen on. Also doubles as the `adapter` label readings from
+/// it are tagged with, so duplicate sightings from overlapping radios stay distinguishable.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AdapterSelector {
+    Index(usize),
+    Name(String),
+}
+
+impl fmt::Display for AdapterSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AdapterSelector::Index(i) => write!(f, "{}", i),
+            AdapterSelector::Name(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_push_interval_seconds() -> u64 {
+    10
+}
+
+fn default_cleanup_period_seconds() -> u64 {
+    1
+}
+
+fn default_stale_timeout_seconds() -> u64 {
+    10
+}
+
+#[derive(Debug, Deserialize)]
+struct RawPrometheusConfig {
+    #[serde(default = "default_true")]
+    enabled: bool,
+}
+
+impl Default for RawPrometheusConfig {
+    fn default() -> Self {
+        RawPrometheusConfig { enabled: true }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct RawStalenessConfig {
+    #[serde(default = "default_cleanup_period_seconds")]
+    cleanup_period_seconds: u64,
+    #[serde(default = "default_stale_timeout_seconds")]
+    stale_timeout_seconds: u64,
+}
+
+impl Default for RawStalenessConfig {
+    fn default() -> Self {
+        RawStalenessConfig {
+            cleanup_period_seconds: default_cleanup_period_seconds(),
+            stale_timeout_seconds: default_stale_timeout_seconds(),
+        }
+    }
+}
+
+/// Discovery scoping, kept separate from `sensors` so a tag can be allow-listed without being
+/// named, and still surface under its advertised local name.
+#[derive(Debug, Deserialize, Default)]
+struct RawDiscoveryConfig {
+    #[serde(default)]
+    addresses: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RawInfluxConfig {
+    #[serde(default = "default_true")]
+    enabled: bool,
+    url: String,
+    database: String,
+    username: Option<String>,
+    password: Option<String>,
+    #[serde(default = "default_push_interval_seconds")]
+    push_interval_seconds: u64,
+}
+
+/// Raw, as-written configuration, keyed by address string so it can be deserialized directly
+/// from TOML.
+#[derive(Debug, Deserialize, Default)]
+struct RawConfig {
+    #[serde(default)]
+    sensors: HashMap<String, SensorConfig>,
+    #[serde(default)]
+    discovery: RawDiscoveryConfig,
+    #[serde(default)]
+    prometheus: RawPrometheusConfig,
+    #[serde(default)]
+    staleness: RawStalenessConfig,
+    influx: Option<RawInfluxConfig>,
+    /// Adapters to listen on concurrently. Empty (the default) means "the first available
+    /// adapter", preserving single-adapter behavior.
+    #[serde(default)]
+    adapters: Vec<RawAdapterSelector>,
+}
+
+/// Collector configuration, loaded from a TOML file.
+///
+/// ```toml
+/// [sensors."AA:BB:CC:DD:EE:FF"]
+/// name = "Living room"
+///
+/// [discovery]
+/// addresses = ["AA:BB:CC:DD:EE:FF", "11:22:33:44:55:66"]
+///
+/// [influx]
+/// url = "http://localhost:8086"
+/// database = "ruuvi"
+/// ```
+#[derive(Debug, Clone)]
+pub struct Config {
+    names: HashMap<BDAddr, String>,
+    discovery_addresses: Vec<BDAddr>,
+    prometheus_enabled: bool,
+    cleanup_period: Duration,
+    stale_timeout: Duration,
+    influx: Option<InfluxConfig>,
+    adapters: Vec<AdapterSelector>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            names: HashMap::new(),
+            discovery_addresses: Vec::new(),
+            prometheus_enabled: true,
+            cleanup_period: Duration::from_secs(default_cleanup_period_seconds()),
+            stale_timeout: Duration::from_secs(default_stale_timeout_seconds()),
+            influx: None,
+            adapters: Vec::new(),
+        }
+    }
+}
+
+impl Config {
+    /// Loads configuration from `path`. Missing files are treated as an empty configuration,
+    /// since naming, the discovery allowlist, and the influx exporter are all optional; the
+    /// Prometheus server stays enabled by default.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        let raw = match fs::read_to_string(path) {
+            Ok(contents) => toml::from_str::<RawConfig>(&contents)
+                .map_err(|e| format!("failed to parse {}: {}", path.display(), e))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => RawConfig::default(),
+            Err(e) => return Err(format!("failed to read {}: {}", path.display(), e)),
+        };
+
+        let mut names = HashMap::with_capacity(raw.sensors.len());
+        for (addr, sensor) in raw.sensors {
+            let addr = BDAddr::from_str(&addr)
+                .map_err(|_| format!("invalid sensor address {:?} in {}", addr, path.display()))?;
+            names.insert(addr, sensor.name);
+        }
+
+        let mut discovery_addresses = Vec::with_capacity(raw.discovery.addresses.len());
+        for addr in raw.discovery.addresses {
+            let addr = BDAddr::from_str(&addr).map_err(|_| {
+                format!("invalid discovery address {:?} in {}", addr, path.display())
+            })?;
+            discovery_addresses.push(addr);
+        }
+
+        let influx = raw.influx.filter(|i| i.enabled).map(|i| InfluxConfig {
+            url: i.url,
+            database: i.database,
+            username: i.username,
+            password: i.password,
+            push_interval: Duration::from_secs(i.push_interval_seconds),
+        });
+
+        let adapters = raw
+            .adapters
+            .into_iter()
+            .map(|a| match a {
+                RawAdapterSelector::Index(i) => AdapterSelector::Index(i),
+                RawAdapterSelector::Name(name) => AdapterSelector::Name(name),
+            })
+            .collect();
+
+        Ok(Config {
+            names,
+            discovery_addresses,
+            prometheus_enabled: raw.prometheus.enabled,
+            cleanup_period: Duration::from_secs(raw.staleness.cleanup_period_seconds),
+            stale_timeout: Duration::from_secs(raw.staleness.stale_timeout_seconds),
+            influx,
+            adapters,
+        })
+    }
+
+    /// The friendly name configured for `address`, if any.
+    pub fn name_for(&self, address: &BDAddr) -> Option<&str> {
+        self.names.get(address).map(String::as_str)
+    }
+
+    /// The addresses explicitly listed under `[discovery]`, used to scope discovery when
+    /// non-empty. Kept independent of `sensors`, so a tag can be allow-listed without being
+    /// named and still surface under its advertised local name.
+    pub fn known_addresses(&self) -> &[BDAddr] {
+        &self.discovery_addresses
+    }
+
+    /// Whether the Prometheus `/metrics` and `/workers` server should be started.
+    pub fn prometheus_enabled(&self) -> bool {
+        self.prometheus_enabled
+    }
+
+    /// The InfluxDB push exporter's configuration, if it's enabled.
+    pub fn influx(&self) -> Option<&InfluxConfig> {
+        self.influx.as_ref()
+    }
+
+    /// How often the gauge cleanup worker sweeps for idle sensors.
+    pub fn cleanup_period(&self) -> Duration {
+        self.cleanup_period
+    }
+
+    /// How long a sensor may go without a fresh measurement before it's considered stale.
+    pub fn stale_timeout(&self) -> Duration {
+        self.stale_timeout
+    }
+
+    /// The bluetooth adapters to listen on concurrently. Empty means "the first available
+    /// adapter", so a single listener is spawned, matching the collector's original behavior.
+    pub fn adapters(&self) -> &[AdapterSelector] {
+        &self.adapters
+    }
+}
@@ -0,0 +1,172 @@
+use crate::worker::{Shutdown, Worker};
+use async_trait::async_trait;
+use hyper::{
+    header::{AUTHORIZATION, CONTENT_TYPE},
+    Body, Client, Method, Request,
+};
+use ruuvi_sensor_protocol::{
+    BatteryPotential, Humidity, MeasurementSequenceNumber, Pressure, SensorValues, Temperature,
+};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::time::interval;
+
+/// Configuration for pushing readings to an InfluxDB 1.x `/write` endpoint.
+#[derive(Clone, Debug)]
+pub struct InfluxConfig {
+    /// Base URL of the InfluxDB server, e.g. `http://localhost:8086`.
+    pub url: String,
+    pub database: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// How often to flush batched readings.
+    pub push_interval: Duration,
+}
+
+/// The most recently seen values for a single tag, ready to be rendered as one InfluxDB line
+/// protocol point.
+struct Point {
+    sequence_number: Option<u16>,
+    temperature: Option<f64>,
+    humidity: Option<f64>,
+    pressure: Option<f64>,
+    battery_potential: Option<f64>,
+    recorded_at: SystemTime,
+}
+
+fn escape_tag(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace(',', "\\,")
+        .replace('=', "\\=")
+        .replace(' ', "\\ ")
+}
+
+fn point_to_line(address: &str, point: &Point) -> String {
+    let mut fields = Vec::new();
+    if let Some(v) = point.sequence_number {
+        fields.push(format!("measurement_sequence_number={}i", v));
+    }
+    if let Some(v) = point.temperature {
+        fields.push(format!("temperature={}", v));
+    }
+    if let Some(v) = point.humidity {
+        fields.push(format!("humidity={}", v));
+    }
+    if let Some(v) = point.pressure {
+        fields.push(format!("pressure={}", v));
+    }
+    if let Some(v) = point.battery_potential {
+        fields.push(format!("battery_potential={}", v));
+    }
+    let nanos = point
+        .recorded_at
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    format!(
+        "ruuvi,address={} {} {}",
+        escape_tag(address),
+        fields.join(","),
+        nanos
+    )
+}
+
+/// Batches the latest reading per tag and periodically pushes them to InfluxDB as an
+/// alternative to the Prometheus `/metrics` pull endpoint, for deployments a Prometheus server
+/// can't reach.
+#[derive(Clone)]
+pub struct InfluxExporter {
+    config: InfluxConfig,
+    points: Arc<Mutex<HashMap<String, Point>>>,
+    client: Client<hyper::client::HttpConnector>,
+}
+
+impl InfluxExporter {
+    pub fn new(config: InfluxConfig) -> Self {
+        InfluxExporter {
+            config,
+            points: Default::default(),
+            client: Client::new(),
+        }
+    }
+
+    /// Records the latest reading for `address`, overwriting any previous one still pending a
+    /// flush, so only one point per tag is ever sent.
+    pub fn record(&self, address: &str, values: &SensorValues) {
+        let point = Point {
+            sequence_number: values.measurement_sequence_number(),
+            temperature: values
+                .temperature_as_millicelsius()
+                .map(|t| f64::from(t) * 1e-3),
+            humidity: values.humidity_as_ppm().map(|h| f64::from(h) * 1e-4),
+            pressure: values.pressure_as_pascals().map(|p| f64::from(p) * 1e-3),
+            battery_potential: values.battery_potential_as_millivolts().map(f64::from),
+            recorded_at: SystemTime::now(),
+        };
+        self.points.lock().unwrap().insert(address.to_string(), point);
+    }
+
+    async fn flush(&self) -> Result<(), String> {
+        let points: Vec<(String, Point)> = self.points.lock().unwrap().drain().collect();
+        if points.is_empty() {
+            return Ok(());
+        }
+
+        let body = points
+            .iter()
+            .map(|(address, point)| point_to_line(address, point))
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        let uri = format!(
+            "{}/write?db={}",
+            self.config.url.trim_end_matches('/'),
+            self.config.database
+        );
+        let mut request = Request::builder()
+            .method(Method::POST)
+            .uri(uri)
+            .header(CONTENT_TYPE, "text/plain; charset=utf-8");
+        if let (Some(username), Some(password)) = (&self.config.username, &self.config.password) {
+            let credentials = base64::encode(format!("{}:{}", username, password));
+            request = request.header(AUTHORIZATION, format!("Basic {}", credentials));
+        }
+        let request = request.body(Body::from(body)).map_err(|e| e.to_string())?;
+
+        let response = self
+            .client
+            .request(request)
+            .await
+            .map_err(|e| format!("failed to reach influxdb: {}", e))?;
+        if !response.status().is_success() {
+            return Err(format!("influxdb responded with {}", response.status()));
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Worker for InfluxExporter {
+    fn name(&self) -> &str {
+        "influx-exporter"
+    }
+
+    async fn run(&self, mut shutdown: Shutdown) -> Result<(), String> {
+        let mut interval = interval(self.config.push_interval);
+        while !shutdown.is_shutdown() {
+            tokio::select! {
+                _ = interval.tick() => {
+                    if let Err(e) = self.flush().await {
+                        error!("failed to push readings to influxdb: {}", e);
+                    }
+                }
+                _ = shutdown.recv() => {}
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,28 +1,117 @@
+use crate::config::{AdapterSelector, Config};
+use crate::influx_exporter::InfluxExporter;
 use crate::metrics_server::serve_metrics;
 use crate::ruuvi_gauges::RuuviGauges;
-use crate::ruuvi_listener::listen_for_tags;
+use crate::ruuvi_listener::{new_pump_slot, BleEventPump, WatchdogSupervisor};
+use crate::watchdog::Watchdog;
+use crate::worker::WorkerManager;
 use prometheus::Registry;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::signal::unix::{signal, SignalKind};
 
 #[macro_use]
 extern crate log;
 
+mod config;
+mod influx_exporter;
 mod metrics_server;
 mod ruuvi_gauges;
 mod ruuvi_listener;
+mod watchdog;
+mod worker;
+
+/// How long the BLE listener may go without a reading before it's considered wedged and the
+/// adapter is reset.
+const WATCHDOG_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Path to the optional TOML configuration file mapping sensor addresses to friendly names.
+const CONFIG_PATH: &str = "ruuvi-collector.toml";
+
+/// Resolves once SIGINT or SIGTERM is received.
+async fn wait_for_shutdown_signal() {
+    let mut sigterm =
+        signal(SignalKind::terminate()).expect("failed to install SIGTERM handler");
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = sigterm.recv() => {}
+    }
+}
 
 #[tokio::main(flavor = "current_thread")]
 async fn main() {
     env_logger::init();
 
+    let config = Arc::new(Config::load(CONFIG_PATH).unwrap_or_else(|e| {
+        warn!("{}, continuing without it", e);
+        Config::default()
+    }));
+
+    // The InfluxDB push exporter, independent of whether the Prometheus pull endpoint runs.
+    let influx_exporter = config.influx().cloned().map(InfluxExporter::new);
+
     // Setup sensor metrics
     let registry = Registry::new();
-    let gauges = RuuviGauges::create_and_register(&registry);
+    let gauges = RuuviGauges::create_and_register(
+        &registry,
+        influx_exporter.clone(),
+        config.cleanup_period(),
+        config.stale_timeout(),
+    );
+
+    // Register the background workers: gauge cleanup, one BLE event pump and watchdog
+    // supervisor pair per configured adapter (so several radios can be listened on
+    // concurrently), and (if enabled) the influx pusher.
+    let adapters = config.adapters().to_vec();
+    let adapters = if adapters.is_empty() {
+        vec![AdapterSelector::Index(0)]
+    } else {
+        adapters
+    };
+
+    let mut workers = WorkerManager::new();
+    workers.spawn(gauges.clone());
+    for selector in adapters {
+        let watchdog = Watchdog::new(WATCHDOG_TIMEOUT);
+        // Shared so the supervisor can stop the pump's thread on reset instead of leaking it.
+        let pump_slot = new_pump_slot();
+        workers.spawn(BleEventPump::new(
+            gauges.clone(),
+            watchdog.clone(),
+            config.clone(),
+            selector.clone(),
+            pump_slot.clone(),
+        ));
+        workers.spawn(WatchdogSupervisor::new(
+            gauges.clone(),
+            watchdog,
+            config.clone(),
+            selector,
+            pump_slot,
+        ));
+    }
+    if let Some(influx_exporter) = influx_exporter {
+        workers.spawn(influx_exporter);
+    }
+    let worker_statuses = workers.handle();
+    // A second, independent subscription to the same shutdown broadcast `workers.shutdown()`
+    // sends on, so the metrics server can stop accepting connections and `main` can return
+    // instead of blocking on `serve_metrics` forever.
+    let metrics_shutdown = workers.subscribe_shutdown();
 
-    // Start listening for ruuvi tags
-    listen_for_tags(gauges);
+    // Shut every worker down cleanly on SIGINT/SIGTERM instead of relying on process death.
+    let shutdown_task = tokio::spawn(async move {
+        wait_for_shutdown_signal().await;
+        info!("Shutdown signal received, stopping workers");
+        workers.shutdown().await;
+    });
 
-    // Start serving metrics
-    if let Err(e) = serve_metrics(registry).await {
-        error!("server error: {}", e);
+    if config.prometheus_enabled() {
+        if let Err(e) = serve_metrics(registry, worker_statuses, metrics_shutdown).await {
+            error!("server error: {}", e);
+        }
     }
+    // Wait for every worker to actually stop before returning, whether or not the metrics
+    // server was running.
+    let _ = shutdown_task.await;
 }
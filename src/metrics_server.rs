@@ -1,18 +1,58 @@
+use crate::worker::{Shutdown, WorkerState, WorkerStatuses};
 use hyper::{
+    header::{HeaderValue, CONTENT_TYPE},
     service::{make_service_fn, service_fn},
     Body, Method, Request, Response, Server, StatusCode,
 };
 use prometheus::{Encoder, Registry, TextEncoder};
 use std::{convert::Infallible, net::SocketAddr};
 
-pub async fn create_metrics_server(registry: Registry) -> Result<(), hyper::error::Error> {
+fn worker_state_str(state: WorkerState) -> &'static str {
+    match state {
+        WorkerState::Active => "active",
+        WorkerState::Idle => "idle",
+        WorkerState::Dead => "dead",
+    }
+}
+
+/// Renders worker statuses as a JSON array of `{name, state, last_error}` objects.
+fn render_workers(workers: &WorkerStatuses) -> String {
+    let entries: Vec<String> = workers
+        .snapshot()
+        .iter()
+        .map(|w| {
+            let last_error = match &w.last_error {
+                Some(e) => format!("{:?}", e),
+                None => "null".to_string(),
+            };
+            format!(
+                "{{\"name\":{:?},\"state\":{:?},\"last_error\":{}}}",
+                w.name,
+                worker_state_str(w.state),
+                last_error
+            )
+        })
+        .collect();
+    format!("[{}]", entries.join(","))
+}
+
+/// Serves `/metrics` and `/workers` until `shutdown` fires, at which point the server stops
+/// accepting new connections and this future resolves, so `main` can actually return after a
+/// shutdown signal instead of blocking on it forever.
+pub async fn serve_metrics(
+    registry: Registry,
+    workers: WorkerStatuses,
+    mut shutdown: Shutdown,
+) -> Result<(), hyper::error::Error> {
     let addr = SocketAddr::from(([0, 0, 0, 0], 8000));
 
     let make_rvc = make_service_fn(|_conn| {
         let registry = registry.clone();
+        let workers = workers.clone();
         async move {
             let service = service_fn(move |req: Request<Body>| {
                 let registry = registry.clone();
+                let workers = workers.clone();
                 async move {
                     let mut response = Response::new(Body::empty());
 
@@ -24,6 +64,12 @@ pub async fn create_metrics_server(registry: Registry) -> Result<(), hyper::erro
                             encoder.encode(&metric_families, &mut buffer).unwrap();
                             *response.body_mut() = Body::from(buffer);
                         }
+                        (&Method::GET, "/workers") => {
+                            *response.body_mut() = Body::from(render_workers(&workers));
+                            response
+                                .headers_mut()
+                                .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+                        }
                         _ => {
                             *response.status_mut() = StatusCode::NOT_FOUND;
                         }
@@ -36,5 +82,8 @@ pub async fn create_metrics_server(registry: Registry) -> Result<(), hyper::erro
         }
     });
 
-    Server::bind(&addr).serve(make_rvc).await
+    Server::bind(&addr)
+        .serve(make_rvc)
+        .with_graceful_shutdown(async move { shutdown.recv().await })
+        .await
 }
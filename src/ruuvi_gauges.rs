@@ -1,16 +1,43 @@
+use crate::influx_exporter::InfluxExporter;
+use crate::worker::{Shutdown, Worker};
+use async_trait::async_trait;
 use btleplug::api::BDAddr;
 use prometheus::{GaugeVec, IntGaugeVec, Opts, Registry};
 use ruuvi_sensor_protocol::{
-    BatteryPotential, Humidity, MeasurementSequenceNumber, MovementCounter, Pressure, SensorValues,
-    Temperature,
+    Acceleration, BatteryPotential, Humidity, MeasurementSequenceNumber, MovementCounter,
+    Pressure, SensorValues, Temperature, TransmitterPower,
 };
 use std::{collections::HashMap, sync::Arc, sync::Mutex, time::Duration};
 use tokio::time::{interval, Instant};
 
-/// Run cleanup job for old sensors every second.
-const CLEANUP_PERIOD: Duration = Duration::from_secs(1);
-/// Consider tags stale and lost if they haven't been sen for 10 seconds.
-const STALE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Identifies the set of `ruuvi_*` fields this collector emits and their units, so a downstream
+/// collector can tell schema revisions apart. Bump whenever fields are added, removed, or their
+/// units change.
+const SCHEMA_VERSION: &str = "2";
+
+const ACCELERATION_AXES: [&str; 3] = ["x", "y", "z"];
+
+/// State tracked per (address, adapter) pair to support the cleanup worker: when it was last
+/// seen, the name and adapter it was last seen under (so the gauges can be removed with the same
+/// labels they were set with), its measurement sequence number, and whether it's currently in a
+/// grace period pending eviction.
+///
+/// A tag isn't culled the instant it crosses `stale_timeout`: it's given one further
+/// `stale_timeout`-long grace period (`grace_deadline`) to produce a single new reading. If its
+/// sequence number hasn't moved from `sequence_number_at_grace_start` by the time the grace
+/// period elapses, it's truly gone quiet and is evicted; if a reading does arrive, grace is
+/// cleared and it's treated as freshly seen. This is what lets a tag on a slow but legitimate
+/// advertising interval (longer than `stale_timeout` but shorter than two of them) survive,
+/// instead of plain wall-clock staleness evicting it every cycle.
+struct SensorState {
+    last_seen: Instant,
+    address: String,
+    name: String,
+    adapter: String,
+    sequence_number: Option<u16>,
+    grace_deadline: Option<Instant>,
+    sequence_number_at_grace_start: Option<u16>,
+}
 
 #[derive(Clone)]
 pub struct RuuviGauges {
@@ -20,27 +47,47 @@ pub struct RuuviGauges {
     battery_potential: GaugeVec,
     movement_counter: IntGaugeVec,
     sequence_number: IntGaugeVec,
-    last_seen: Arc<Mutex<HashMap<String, Instant>>>,
+    acceleration: IntGaugeVec,
+    acceleration_magnitude: GaugeVec,
+    tx_power: IntGaugeVec,
+    last_seen_seconds: GaugeVec,
+    /// Keyed by `"{address}@{adapter}"`, so the same tag seen by two adapters is tracked (and
+    /// culled) independently.
+    sensors: Arc<Mutex<HashMap<String, SensorState>>>,
+    influx: Option<InfluxExporter>,
+    cleanup_period: Duration,
+    stale_timeout: Duration,
 }
 
 impl RuuviGauges {
     /// Creates new gauges and registers them for the given registry.
-    pub fn create_and_register(registry: &Registry) -> Self {
+    ///
+    /// `influx`, if given, also receives every reading for push-based export, independently of
+    /// the Prometheus gauges. `cleanup_period` controls how often the idle-sensor cleanup worker
+    /// runs, and `stale_timeout` how long a sensor may go without a fresh measurement before it
+    /// enters a grace period; its gauges are removed if no reading arrives within one further
+    /// `stale_timeout`.
+    pub fn create_and_register(
+        registry: &Registry,
+        influx: Option<InfluxExporter>,
+        cleanup_period: Duration,
+        stale_timeout: Duration,
+    ) -> Self {
         // Fill in sensor metrics
         let gauges = RuuviGauges {
             temperature: GaugeVec::new(
                 Opts::new("ruuvi_temperature", "temperature reported by ruuvi sensor"),
-                &["address"],
+                &["address", "name", "adapter"],
             )
             .unwrap(),
             humidity: GaugeVec::new(
                 Opts::new("ruuvi_humidity", "humidity reported by ruuvi sensor"),
-                &["address"],
+                &["address", "name", "adapter"],
             )
             .unwrap(),
             pressure: GaugeVec::new(
                 Opts::new("ruuvi_pressure", "pressure reported by ruuvi sensor"),
-                &["address"],
+                &["address", "name", "adapter"],
             )
             .unwrap(),
             battery_potential: GaugeVec::new(
@@ -48,7 +95,7 @@ impl RuuviGauges {
                     "ruuvi_battery_potential",
                     "battery_potential reported by ruuvi sensor",
                 ),
-                &["address"],
+                &["address", "name", "adapter"],
             )
             .unwrap(),
             movement_counter: IntGaugeVec::new(
@@ -56,7 +103,7 @@ impl RuuviGauges {
                     "ruuvi_movement_counter",
                     "movement_counter reported by ruuvi sensor",
                 ),
-                &["address"],
+                &["address", "name", "adapter"],
             )
             .unwrap(),
             sequence_number: IntGaugeVec::new(
@@ -64,10 +111,42 @@ impl RuuviGauges {
                     "ruuvi_sequence_number",
                     "sequence_number reported by ruuvi sensor",
                 ),
-                &["address"],
+                &["address", "name", "adapter"],
+            )
+            .unwrap(),
+            acceleration: IntGaugeVec::new(
+                Opts::new(
+                    "ruuvi_acceleration",
+                    "acceleration reported by ruuvi sensor, in milli-G",
+                ),
+                &["address", "name", "adapter", "axis"],
+            )
+            .unwrap(),
+            acceleration_magnitude: GaugeVec::new(
+                Opts::new(
+                    "ruuvi_acceleration_magnitude",
+                    "magnitude of the acceleration vector reported by ruuvi sensor, in milli-G",
+                ),
+                &["address", "name", "adapter"],
+            )
+            .unwrap(),
+            tx_power: IntGaugeVec::new(
+                Opts::new("ruuvi_tx_power", "tx_power reported by ruuvi sensor, in dBm"),
+                &["address", "name", "adapter"],
+            )
+            .unwrap(),
+            last_seen_seconds: GaugeVec::new(
+                Opts::new(
+                    "ruuvi_last_seen_seconds",
+                    "seconds since a measurement was last seen for this sensor",
+                ),
+                &["address", "name", "adapter"],
             )
             .unwrap(),
-            last_seen: Default::default(),
+            sensors: Default::default(),
+            influx,
+            cleanup_period,
+            stale_timeout,
         };
         // Register all of them
         registry
@@ -88,101 +167,270 @@ impl RuuviGauges {
         registry
             .register(Box::new(gauges.sequence_number.clone()))
             .unwrap();
+        registry
+            .register(Box::new(gauges.acceleration.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(gauges.acceleration_magnitude.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(gauges.tx_power.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(gauges.last_seen_seconds.clone()))
+            .unwrap();
 
-        // A periodic job for removing too old sensor readings.
-        // XXX: This job is not removed if the gauges gets dropped.
-        tokio::spawn({
-            let gauges = gauges.clone();
-            async move {
-                let mut interval = interval(CLEANUP_PERIOD);
-                loop {
-                    interval.tick().await;
-                    let mut last_seen = gauges.last_seen.lock().unwrap();
-                    let now = Instant::now();
-                    last_seen.retain(|address, &mut value| {
-                        if now - value < STALE_TIMEOUT {
-                            true
-                        } else {
-                            gauges.remove_sensor_values(address);
-                            false
-                        }
-                    });
-                }
-            }
-        });
+        // An info-style gauge describing the emitted field set, for downstream collectors to
+        // detect the measurement schema.
+        let schema_version = GaugeVec::new(
+            Opts::new(
+                "ruuvi_collector_schema_version",
+                "describes the set of ruuvi_* fields emitted by this collector and their units",
+            ),
+            &["version", "acceleration_unit", "tx_power_unit"],
+        )
+        .unwrap();
+        registry.register(Box::new(schema_version.clone())).unwrap();
+        schema_version
+            .with_label_values(&[SCHEMA_VERSION, "milli-g", "dbm"])
+            .set(1.0);
 
         gauges
     }
 
-    /// Updates the exposed sensor values for the given sensor.
-    pub fn update_sensor_values(&self, address: BDAddr, values: SensorValues) {
+    /// Updates the exposed sensor values for the given sensor. `name` is the friendly name to
+    /// label the series with, falling back to the address when the sensor is unmapped. `adapter`
+    /// identifies which configured bluetooth adapter produced the reading, so the same tag seen
+    /// by two overlapping radios shows up as distinguishable series rather than clobbering each
+    /// other.
+    pub fn update_sensor_values(
+        &self,
+        address: BDAddr,
+        name: &str,
+        adapter: &str,
+        values: SensorValues,
+    ) {
         let address = format!("{}", address);
+        let key = format!("{}@{}", address, adapter);
 
-        // Update last seen status
+        if let Some(influx) = &self.influx {
+            influx.record(&address, &values);
+        }
+
+        let sequence_number = values.measurement_sequence_number();
+
+        // Update last seen status. A fresh reading always clears any pending grace period: the
+        // tag just proved it's alive.
         {
-            let mut last_seen = self.last_seen.lock().unwrap();
-            last_seen.insert(address.clone(), Instant::now());
+            let mut sensors = self.sensors.lock().unwrap();
+            sensors.insert(
+                key,
+                SensorState {
+                    last_seen: Instant::now(),
+                    address: address.clone(),
+                    name: name.to_string(),
+                    adapter: adapter.to_string(),
+                    sequence_number,
+                    grace_deadline: None,
+                    sequence_number_at_grace_start: None,
+                },
+            );
         }
+        self.last_seen_seconds
+            .with_label_values(&[&address, name, adapter])
+            .set(0.0);
 
         // Update each sensor reading, or remove if the value couldn't be parsed.
         if let Some(temperature) = values.temperature_as_millicelsius() {
             self.temperature
-                .with_label_values(&[&address])
+                .with_label_values(&[&address, name, adapter])
                 .set(f64::from(temperature) * 1e-3);
         } else {
-            let _ = self.temperature.remove_label_values(&[&address]);
+            let _ = self
+                .temperature
+                .remove_label_values(&[&address, name, adapter]);
         }
 
         if let Some(humidity) = values.humidity_as_ppm() {
             self.humidity
-                .with_label_values(&[&address])
+                .with_label_values(&[&address, name, adapter])
                 .set(f64::from(humidity) * 1e-4);
         } else {
-            let _ = self.humidity.remove_label_values(&[&address]);
+            let _ = self
+                .humidity
+                .remove_label_values(&[&address, name, adapter]);
         }
 
         if let Some(pressure) = values.pressure_as_pascals() {
             self.pressure
-                .with_label_values(&[&address])
+                .with_label_values(&[&address, name, adapter])
                 .set(f64::from(pressure) * 1e-3);
         } else {
-            let _ = self.pressure.remove_label_values(&[&address]);
+            let _ = self
+                .pressure
+                .remove_label_values(&[&address, name, adapter]);
         }
 
         if let Some(potential) = values.battery_potential_as_millivolts() {
             self.battery_potential
-                .with_label_values(&[&address])
+                .with_label_values(&[&address, name, adapter])
                 .set(f64::from(potential));
         } else {
-            let _ = self.battery_potential.remove_label_values(&[&address]);
+            let _ = self
+                .battery_potential
+                .remove_label_values(&[&address, name, adapter]);
         }
 
         if let Some(counter) = values.movement_counter() {
             self.movement_counter
-                .with_label_values(&[&address])
+                .with_label_values(&[&address, name, adapter])
                 .set(counter.into());
         } else {
-            let _ = self.movement_counter.remove_label_values(&[&address]);
+            let _ = self
+                .movement_counter
+                .remove_label_values(&[&address, name, adapter]);
         }
 
         if let Some(sequence) = values.measurement_sequence_number() {
             self.sequence_number
-                .with_label_values(&[&address])
+                .with_label_values(&[&address, name, adapter])
                 .set(sequence.into());
         } else {
-            let _ = self.sequence_number.remove_label_values(&[&address]);
+            let _ = self
+                .sequence_number
+                .remove_label_values(&[&address, name, adapter]);
+        }
+
+        if let Some((x, y, z)) = values.acceleration_vector_as_milli_g() {
+            self.acceleration
+                .with_label_values(&[&address, name, adapter, ACCELERATION_AXES[0]])
+                .set(x.into());
+            self.acceleration
+                .with_label_values(&[&address, name, adapter, ACCELERATION_AXES[1]])
+                .set(y.into());
+            self.acceleration
+                .with_label_values(&[&address, name, adapter, ACCELERATION_AXES[2]])
+                .set(z.into());
+            let magnitude =
+                (f64::from(x).powi(2) + f64::from(y).powi(2) + f64::from(z).powi(2)).sqrt();
+            self.acceleration_magnitude
+                .with_label_values(&[&address, name, adapter])
+                .set(magnitude);
+        } else {
+            for axis in ACCELERATION_AXES {
+                let _ = self
+                    .acceleration
+                    .remove_label_values(&[&address, name, adapter, axis]);
+            }
+            let _ = self
+                .acceleration_magnitude
+                .remove_label_values(&[&address, name, adapter]);
+        }
+
+        if let Some(tx_power) = values.tx_power_as_dbm() {
+            self.tx_power
+                .with_label_values(&[&address, name, adapter])
+                .set(tx_power.into());
+        } else {
+            let _ = self
+                .tx_power
+                .remove_label_values(&[&address, name, adapter]);
         }
     }
 
-    /// Removes sensor values for the given address from the exposed metrics.
-    fn remove_sensor_values(&self, address: &str) {
+    /// Removes sensor values for the given address, name, and adapter from the exposed metrics.
+    fn remove_sensor_values(&self, address: &str, name: &str, adapter: &str) {
         // Explicitly ignore the removal status. Either the value is removed correctly, or it never existed in the first
         // place.
-        let _ = self.temperature.remove_label_values(&[&address]);
-        let _ = self.humidity.remove_label_values(&[&address]);
-        let _ = self.pressure.remove_label_values(&[&address]);
-        let _ = self.battery_potential.remove_label_values(&[&address]);
-        let _ = self.movement_counter.remove_label_values(&[&address]);
-        let _ = self.sequence_number.remove_label_values(&[&address]);
+        let _ = self
+            .temperature
+            .remove_label_values(&[address, name, adapter]);
+        let _ = self
+            .humidity
+            .remove_label_values(&[address, name, adapter]);
+        let _ = self
+            .pressure
+            .remove_label_values(&[address, name, adapter]);
+        let _ = self
+            .battery_potential
+            .remove_label_values(&[address, name, adapter]);
+        let _ = self
+            .movement_counter
+            .remove_label_values(&[address, name, adapter]);
+        let _ = self
+            .sequence_number
+            .remove_label_values(&[address, name, adapter]);
+        for axis in ACCELERATION_AXES {
+            let _ = self
+                .acceleration
+                .remove_label_values(&[address, name, adapter, axis]);
+        }
+        let _ = self
+            .acceleration_magnitude
+            .remove_label_values(&[address, name, adapter]);
+        let _ = self
+            .tx_power
+            .remove_label_values(&[address, name, adapter]);
+        let _ = self
+            .last_seen_seconds
+            .remove_label_values(&[address, name, adapter]);
+    }
+}
+
+/// Periodically removes sensor values that haven't been seen in a while, as a managed
+/// [`Worker`] instead of a task the gauges spawn and never clean up after themselves.
+#[async_trait]
+impl Worker for RuuviGauges {
+    fn name(&self) -> &str {
+        "gauge-cleanup"
+    }
+
+    async fn run(&self, mut shutdown: Shutdown) -> Result<(), String> {
+        let mut interval = interval(self.cleanup_period);
+        while !shutdown.is_shutdown() {
+            tokio::select! {
+                _ = interval.tick() => {
+                    let mut sensors = self.sensors.lock().unwrap();
+                    let now = Instant::now();
+                    sensors.retain(|_key, state| match state.grace_deadline {
+                        None => {
+                            if now - state.last_seen >= self.stale_timeout {
+                                // Crossing the timeout doesn't cull the tag outright: give it one
+                                // more stale_timeout-long window to advance its sequence number
+                                // before giving up on it.
+                                state.grace_deadline = Some(now + self.stale_timeout);
+                                state.sequence_number_at_grace_start = state.sequence_number;
+                            } else {
+                                self.last_seen_seconds
+                                    .with_label_values(&[&state.address, &state.name, &state.adapter])
+                                    .set((now - state.last_seen).as_secs_f64());
+                            }
+                            true
+                        }
+                        Some(deadline) => {
+                            if state.sequence_number != state.sequence_number_at_grace_start {
+                                // A new reading arrived during the grace period: it's alive after all.
+                                state.grace_deadline = None;
+                                state.sequence_number_at_grace_start = None;
+                                self.last_seen_seconds
+                                    .with_label_values(&[&state.address, &state.name, &state.adapter])
+                                    .set((now - state.last_seen).as_secs_f64());
+                                true
+                            } else if now >= deadline {
+                                self.remove_sensor_values(&state.address, &state.name, &state.adapter);
+                                false
+                            } else {
+                                self.last_seen_seconds
+                                    .with_label_values(&[&state.address, &state.name, &state.adapter])
+                                    .set((now - state.last_seen).as_secs_f64());
+                                true
+                            }
+                        }
+                    });
+                }
+                _ = shutdown.recv() => {}
+            }
+        }
+        Ok(())
     }
 }
@@ -1,14 +1,58 @@
+use crate::config::{AdapterSelector, Config};
 use crate::ruuvi_gauges::RuuviGauges;
+use crate::watchdog::Watchdog;
+use crate::worker::{Shutdown, Worker};
+use async_trait::async_trait;
 use btleplug::{
     api::{BDAddr, Central, CentralEvent, Peripheral},
-    bluez::{adapter::ConnectedAdapter, manager::Manager},
+    bluez::{
+        adapter::{Adapter, ConnectedAdapter},
+        manager::Manager,
+    },
 };
 use ruuvi_sensor_protocol::SensorValues;
 use std::{
+    collections::HashSet,
     convert::TryInto,
-    sync::{atomic::Ordering, Arc},
+    sync::mpsc::RecvTimeoutError,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
     thread::spawn,
+    time::Duration,
 };
+use tokio::runtime::Handle;
+
+/// How long a worker waits before retrying adapter setup after a failure, so a missing or
+/// momentarily busy adapter doesn't abort the process.
+const ADAPTER_RETRY_DELAY: Duration = Duration::from_secs(10);
+
+/// How often a pump thread polls its cancellation flag between events, so it notices a reset
+/// promptly instead of blocking on `recv()` forever once its adapter is torn down.
+const PUMP_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Lets a later reset stop an earlier pump thread instead of abandoning it to block forever on
+/// its now-defunct adapter's event channel.
+pub(crate) struct PumpHandle {
+    cancel: Arc<AtomicBool>,
+}
+
+impl PumpHandle {
+    fn cancel(&self) {
+        self.cancel.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Holds the currently running pump thread's handle, if any, so the next reset can stop it
+/// before starting a replacement. Shared between a [`BleEventPump`] and its [`WatchdogSupervisor`]
+/// since they take turns (re)starting the same adapter's pump.
+pub type PumpSlot = Arc<Mutex<Option<PumpHandle>>>;
+
+/// Creates an empty [`PumpSlot`] for a not-yet-started adapter.
+pub fn new_pump_slot() -> PumpSlot {
+    Arc::new(Mutex::new(None))
+}
 
 /// Parses manufacturer-specific data.
 fn parse_manufacturer_data(data: &Vec<u8>) -> Option<SensorValues> {
@@ -20,63 +64,320 @@ fn parse_manufacturer_data(data: &Vec<u8>) -> Option<SensorValues> {
     }
 }
 
+/// Resolves the friendly name to label `address`'s metrics with: the configured name if there is
+/// one, otherwise the peripheral's advertised local name, otherwise the address itself.
+fn resolve_name(address: BDAddr, local_name: Option<String>, config: &Config) -> String {
+    config
+        .name_for(&address)
+        .map(str::to_string)
+        .or(local_name)
+        .unwrap_or_else(|| format!("{}", address))
+}
+
 /// Handles bluetooth event.
 fn on_event_with_address(
     central: &ConnectedAdapter,
     address: BDAddr,
-) -> Option<(BDAddr, SensorValues)> {
-    parse_manufacturer_data(
-        &central
-            .peripheral(address)?
-            .properties()
-            .manufacturer_data?,
-    )
-    .and_then(|data| Some((address, data)))
+    config: &Config,
+) -> Option<(BDAddr, String, SensorValues)> {
+    let properties = central.peripheral(address)?.properties();
+    let values = parse_manufacturer_data(&properties.manufacturer_data?)?;
+    let name = resolve_name(address, properties.local_name, config);
+    Some((address, name, values))
 }
 
-/// Parses bluetooth event.
+/// Parses bluetooth event, ignoring addresses outside `allowlist` (when one is configured) so
+/// non-Ruuvi peripherals aren't even parsed.
 pub fn parse_event(
     central: &ConnectedAdapter,
     event: CentralEvent,
-) -> Option<(BDAddr, SensorValues)> {
-    match event {
-        CentralEvent::DeviceDiscovered(address) => on_event_with_address(central, address),
-        CentralEvent::DeviceUpdated(address) => on_event_with_address(central, address),
-        CentralEvent::DeviceDisconnected(_) => None,
-        CentralEvent::DeviceLost(_) => None,
-        CentralEvent::DeviceConnected(_) => None,
+    config: &Config,
+    allowlist: Option<&HashSet<BDAddr>>,
+) -> Option<(BDAddr, String, SensorValues)> {
+    let address = match event {
+        CentralEvent::DeviceDiscovered(address) => address,
+        CentralEvent::DeviceUpdated(address) => address,
+        CentralEvent::DeviceDisconnected(_) => return None,
+        CentralEvent::DeviceLost(_) => return None,
+        CentralEvent::DeviceConnected(_) => return None,
+    };
+    if let Some(allowlist) = allowlist {
+        if !allowlist.contains(&address) {
+            return None;
+        }
     }
+    on_event_with_address(central, address, config)
 }
 
-/// Starts for listening for tags.
-pub fn listen_for_tags(gauges: RuuviGauges) {
-    // Get a bluetooth adapter and setup it
-    let manager = Manager::new().unwrap();
-    let adapters = manager.adapters().unwrap();
-    let adapter = adapters.into_iter().nth(0).unwrap();
-    let adapter = manager.down(&adapter).unwrap();
-    let adapter = manager.up(&adapter).unwrap();
-    let central = Arc::new(adapter.connect().unwrap());
+/// Resets `adapter` and starts scanning on it, pumping parsed events into `gauges` and petting
+/// `watchdog` every time a reading comes in. Discovery is scoped to `config`'s known addresses
+/// when any are configured. `adapter_label` tags every reading so sightings from this adapter
+/// stay distinguishable from the same tag seen by another one.
+///
+/// Before spawning the new pump thread, cancels whatever thread is currently recorded in
+/// `pump_slot` (a previous reset's pump, reading from an adapter that's since been torn down), so
+/// resets don't leak one OS thread each.
+fn start_scanning(
+    manager: &Manager,
+    adapter: Adapter,
+    gauges: RuuviGauges,
+    watchdog: Watchdog,
+    config: Arc<Config>,
+    adapter_label: String,
+    pump_slot: &PumpSlot,
+) -> Result<(), String> {
+    let adapter = manager
+        .down(&adapter)
+        .map_err(|e| format!("failed to reset adapter {}: {}", adapter_label, e))?;
+    let adapter = manager
+        .up(&adapter)
+        .map_err(|e| format!("failed to bring up adapter {}: {}", adapter_label, e))?;
+    let central = Arc::new(
+        adapter
+            .connect()
+            .map_err(|e| format!("failed to connect to adapter {}: {}", adapter_label, e))?,
+    );
     central.scan_enabled.store(false, Ordering::SeqCst);
     central.filter_duplicates(false);
 
+    let known_addresses = config.known_addresses();
+    let allowlist = if known_addresses.is_empty() {
+        None
+    } else {
+        Some(known_addresses.iter().copied().collect::<HashSet<_>>())
+    };
+
     // Create a channel between rumble callback events and tokio async handler
-    let receiver = central.event_receiver().unwrap();
+    let receiver = central
+        .event_receiver()
+        .ok_or_else(|| format!("adapter {} has no event receiver", adapter_label))?;
+    let handle = Handle::current();
+
+    // Stop the previous pump thread (if any) before handing out its replacement's handle, so
+    // at most one thread is ever blocked on a given adapter's channel.
+    let cancel = Arc::new(AtomicBool::new(false));
+    {
+        let mut slot = pump_slot.lock().unwrap();
+        if let Some(previous) = slot.take() {
+            previous.cancel();
+        }
+        *slot = Some(PumpHandle {
+            cancel: cancel.clone(),
+        });
+    }
 
     // Handle ble events from the channel
     spawn({
         let central = central.clone();
         move || loop {
-            if let Ok(event) = receiver.recv() {
-                if let Some((address, values)) = parse_event(&central, event) {
-                    gauges.update_sensor_values(address, values);
+            if cancel.load(Ordering::SeqCst) {
+                break;
+            }
+            match receiver.recv_timeout(PUMP_POLL_INTERVAL) {
+                Ok(event) => {
+                    if let Some((address, name, values)) =
+                        parse_event(&central, event, &config, allowlist.as_ref())
+                    {
+                        handle.block_on(watchdog.pet());
+                        gauges.update_sensor_values(address, &name, &adapter_label, values);
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => {
+                    eprintln!("Error receiving messages");
+                    break;
                 }
-            } else {
-                eprintln!("Error receiving messages");
             }
         }
     });
 
     // Listen for tags
-    central.start_scan().unwrap();
+    central
+        .start_scan()
+        .map_err(|e| format!("failed to start scan: {}", e))
+}
+
+/// Resolves `selector` against the bluetooth adapters `manager` reports.
+fn select_adapter(manager: &Manager, selector: &AdapterSelector) -> Result<Adapter, String> {
+    let adapters = manager
+        .adapters()
+        .map_err(|e| format!("failed to list bluetooth adapters: {}", e))?;
+    match selector {
+        AdapterSelector::Index(index) => adapters
+            .into_iter()
+            .nth(*index)
+            .ok_or_else(|| format!("no bluetooth adapter at index {}", index)),
+        AdapterSelector::Name(name) => adapters
+            .into_iter()
+            .find(|adapter| &adapter.name == name)
+            .ok_or_else(|| format!("no bluetooth adapter named {:?}", name)),
+    }
+}
+
+/// Resolves and starts scanning on `selector`, retrying with [`ADAPTER_RETRY_DELAY`] between
+/// attempts (instead of aborting the process) until it succeeds or `shutdown` fires.
+async fn select_and_scan_with_retry(
+    manager: &Manager,
+    selector: &AdapterSelector,
+    gauges: &RuuviGauges,
+    watchdog: &Watchdog,
+    config: &Arc<Config>,
+    pump_slot: &PumpSlot,
+    shutdown: &mut Shutdown,
+) {
+    loop {
+        let outcome = select_adapter(manager, selector).and_then(|adapter| {
+            start_scanning(
+                manager,
+                adapter,
+                gauges.clone(),
+                watchdog.clone(),
+                config.clone(),
+                selector.to_string(),
+                pump_slot,
+            )
+        });
+        match outcome {
+            Ok(()) => return,
+            Err(e) => error!(
+                "failed to start scanning on adapter {}: {}, retrying in {:?}",
+                selector, e, ADAPTER_RETRY_DELAY
+            ),
+        }
+        tokio::select! {
+            _ = tokio::time::delay_for(ADAPTER_RETRY_DELAY) => {}
+            _ = shutdown.recv() => return,
+        }
+    }
+}
+
+/// A [`Worker`] that scans for Ruuvi tags on one configured bluetooth adapter and feeds readings
+/// into `gauges`, petting `watchdog` on every one received. One instance is spawned per
+/// configured adapter, so several radios can be listened on concurrently.
+///
+/// The event pump itself runs on its own OS thread, since btleplug's event channel is
+/// synchronous; this worker's `run` just keeps the adapter set up until shutdown is requested.
+/// A bad or missing adapter selection is logged and retried rather than aborting the process.
+pub struct BleEventPump {
+    gauges: RuuviGauges,
+    watchdog: Watchdog,
+    config: Arc<Config>,
+    selector: AdapterSelector,
+    pump_slot: PumpSlot,
+    worker_name: String,
+}
+
+impl BleEventPump {
+    /// `pump_slot` should be the same one given to the [`WatchdogSupervisor`] for this adapter,
+    /// so a watchdog-triggered reset can stop this worker's pump thread instead of leaking it.
+    pub fn new(
+        gauges: RuuviGauges,
+        watchdog: Watchdog,
+        config: Arc<Config>,
+        selector: AdapterSelector,
+        pump_slot: PumpSlot,
+    ) -> Self {
+        let worker_name = format!("ble-event-pump[{}]", selector);
+        BleEventPump {
+            gauges,
+            watchdog,
+            config,
+            selector,
+            pump_slot,
+            worker_name,
+        }
+    }
+}
+
+#[async_trait]
+impl Worker for BleEventPump {
+    fn name(&self) -> &str {
+        &self.worker_name
+    }
+
+    async fn run(&self, mut shutdown: Shutdown) -> Result<(), String> {
+        let manager =
+            Manager::new().map_err(|e| format!("failed to create bluetooth manager: {}", e))?;
+
+        select_and_scan_with_retry(
+            &manager,
+            &self.selector,
+            &self.gauges,
+            &self.watchdog,
+            &self.config,
+            &self.pump_slot,
+            &mut shutdown,
+        )
+        .await;
+
+        shutdown.recv().await;
+        Ok(())
+    }
+}
+
+/// A [`Worker`] that watches `watchdog` and, if it ever expires because its adapter's event pump
+/// has stalled, tears down and reinitializes that bluetooth adapter so scanning resumes.
+pub struct WatchdogSupervisor {
+    gauges: RuuviGauges,
+    watchdog: Watchdog,
+    config: Arc<Config>,
+    selector: AdapterSelector,
+    pump_slot: PumpSlot,
+    worker_name: String,
+}
+
+impl WatchdogSupervisor {
+    /// `pump_slot` should be the same one given to the [`BleEventPump`] for this adapter, so a
+    /// reset here stops that worker's pump thread instead of leaking it.
+    pub fn new(
+        gauges: RuuviGauges,
+        watchdog: Watchdog,
+        config: Arc<Config>,
+        selector: AdapterSelector,
+        pump_slot: PumpSlot,
+    ) -> Self {
+        let worker_name = format!("watchdog-supervisor[{}]", selector);
+        WatchdogSupervisor {
+            gauges,
+            watchdog,
+            config,
+            selector,
+            pump_slot,
+            worker_name,
+        }
+    }
+}
+
+#[async_trait]
+impl Worker for WatchdogSupervisor {
+    fn name(&self) -> &str {
+        &self.worker_name
+    }
+
+    async fn run(&self, mut shutdown: Shutdown) -> Result<(), String> {
+        let manager =
+            Manager::new().map_err(|e| format!("failed to create bluetooth manager: {}", e))?;
+        loop {
+            tokio::select! {
+                _ = self.watchdog.wait() => {
+                    warn!("Watchdog expired on adapter {}, resetting it", self.selector);
+                    select_and_scan_with_retry(
+                        &manager,
+                        &self.selector,
+                        &self.gauges,
+                        &self.watchdog,
+                        &self.config,
+                        &self.pump_slot,
+                        &mut shutdown,
+                    )
+                    .await;
+                    // Re-arm the watchdog immediately: otherwise its deadline is still expired
+                    // from before the reset, and we'd loop straight back into another reset
+                    // before a real reading ever has a chance to pet it.
+                    self.watchdog.pet().await;
+                }
+                _ = shutdown.recv() => return Ok(()),
+            }
+        }
+    }
 }
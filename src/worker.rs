@@ -0,0 +1,162 @@
+use async_trait::async_trait;
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+
+/// Lifecycle state of a registered [`Worker`], as reported by the `/workers` status endpoint.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WorkerState {
+    /// The worker's `run` future is still executing.
+    Active,
+    /// `run` returned normally, typically because shutdown was requested.
+    Idle,
+    /// `run` returned an error, or its task panicked.
+    Dead,
+}
+
+/// A background task managed by a [`WorkerManager`].
+///
+/// Implementors should loop doing their work until `shutdown` fires, then return `Ok(())`.
+#[async_trait]
+pub trait Worker: Send + Sync {
+    /// A short, stable name identifying this worker, e.g. for logs and the `/workers` endpoint.
+    fn name(&self) -> &str;
+
+    /// Runs the worker until it either fails or observes `shutdown`.
+    async fn run(&self, shutdown: Shutdown) -> Result<(), String>;
+}
+
+/// A shutdown signal handed to each [`Worker`], backed by a shared broadcast channel.
+///
+/// Mirrors the common tokio graceful-shutdown pattern: cloning the sender and subscribing once
+/// per worker means a single `send` wakes every worker, and `recv` is idempotent once fired.
+pub struct Shutdown {
+    shutdown: bool,
+    notify: broadcast::Receiver<()>,
+}
+
+impl Shutdown {
+    fn new(notify: broadcast::Receiver<()>) -> Self {
+        Shutdown {
+            shutdown: false,
+            notify,
+        }
+    }
+
+    /// Returns `true` if shutdown has already been observed by a previous call to `recv`.
+    pub fn is_shutdown(&self) -> bool {
+        self.shutdown
+    }
+
+    /// Waits for the shutdown signal. Returns immediately if it has already fired.
+    pub async fn recv(&mut self) {
+        if self.shutdown {
+            return;
+        }
+        let _ = self.notify.recv().await;
+        self.shutdown = true;
+    }
+}
+
+/// Snapshot of a single worker's status, as reported by the `/workers` endpoint.
+pub struct WorkerStatusSnapshot {
+    pub name: String,
+    pub state: WorkerState,
+    pub last_error: Option<String>,
+}
+
+struct WorkerStatus {
+    name: String,
+    state: WorkerState,
+    last_error: Option<String>,
+}
+
+/// A read-only, cheaply cloneable handle onto the statuses of workers registered with a
+/// [`WorkerManager`]. Intended to be shared into the metrics server to back `/workers`.
+#[derive(Clone)]
+pub struct WorkerStatuses(Vec<Arc<Mutex<WorkerStatus>>>);
+
+impl WorkerStatuses {
+    pub fn snapshot(&self) -> Vec<WorkerStatusSnapshot> {
+        self.0
+            .iter()
+            .map(|status| {
+                let status = status.lock().unwrap();
+                WorkerStatusSnapshot {
+                    name: status.name.clone(),
+                    state: status.state,
+                    last_error: status.last_error.clone(),
+                }
+            })
+            .collect()
+    }
+}
+
+/// Owns the lifetime of every registered [`Worker`]: spawns each as its own task, tracks its
+/// state, and can broadcast a shutdown signal and wait for all of them to exit.
+pub struct WorkerManager {
+    shutdown_tx: broadcast::Sender<()>,
+    workers: Vec<(JoinHandle<()>, Arc<Mutex<WorkerStatus>>)>,
+}
+
+impl WorkerManager {
+    pub fn new() -> Self {
+        let (shutdown_tx, _) = broadcast::channel(1);
+        WorkerManager {
+            shutdown_tx,
+            workers: Vec::new(),
+        }
+    }
+
+    /// Spawns `worker` as a managed task, subscribed to this manager's shutdown signal.
+    pub fn spawn<W: Worker + 'static>(&mut self, worker: W) {
+        let status = Arc::new(Mutex::new(WorkerStatus {
+            name: worker.name().to_string(),
+            state: WorkerState::Active,
+            last_error: None,
+        }));
+        let shutdown = Shutdown::new(self.shutdown_tx.subscribe());
+        let task_status = status.clone();
+        let handle = tokio::spawn(async move {
+            // Run the worker in its own task so a panic is observed here and reported as `Dead`
+            // right away, instead of leaving the status `Active` until shutdown.
+            let result = tokio::spawn(async move { worker.run(shutdown).await }).await;
+            let mut status = task_status.lock().unwrap();
+            match result {
+                Ok(Ok(())) => status.state = WorkerState::Idle,
+                Ok(Err(e)) => {
+                    status.state = WorkerState::Dead;
+                    status.last_error = Some(e);
+                }
+                Err(e) => {
+                    status.state = WorkerState::Dead;
+                    status.last_error = Some(format!("worker task panicked: {}", e));
+                }
+            }
+        });
+        self.workers.push((handle, status));
+    }
+
+    /// Returns a cloneable handle for reading worker statuses, e.g. to serve `/workers`.
+    pub fn handle(&self) -> WorkerStatuses {
+        WorkerStatuses(self.workers.iter().map(|(_, status)| status.clone()).collect())
+    }
+
+    /// Subscribes to this manager's shutdown signal without triggering it, so callers other than
+    /// workers (e.g. a server wired up with graceful shutdown) can observe the same signal.
+    pub fn subscribe_shutdown(&self) -> Shutdown {
+        Shutdown::new(self.shutdown_tx.subscribe())
+    }
+
+    /// Broadcasts the shutdown signal to every registered worker and waits for them all to exit.
+    pub async fn shutdown(self) {
+        let _ = self.shutdown_tx.send(());
+        for (handle, status) in self.workers {
+            if let Err(e) = handle.await {
+                let mut status = status.lock().unwrap();
+                status.state = WorkerState::Dead;
+                status.last_error = Some(format!("worker task panicked: {}", e));
+            }
+        }
+    }
+}